@@ -0,0 +1,155 @@
+use bevy::{
+    prelude::*,
+    tasks::{TaskPool, TaskPoolBuilder},
+};
+use bevy_eventwork::{
+    async_channel::{Receiver, Sender},
+    async_trait,
+    error::NetworkError,
+    managers::NetworkProvider,
+    ConnectionId, EventworkPlugin, EventworkRuntime, Network, NetworkEvent, NetworkMessage,
+    NetworkPacket,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures_lite::{future, stream};
+use serde::{Deserialize, Serialize};
+
+/// A provider whose connections never receive anything and discard everything sent to them,
+/// so the benchmarks only measure eventwork's own serialization and queueing.
+#[derive(Default)]
+struct DiscardProvider;
+
+#[derive(Clone, Resource)]
+struct DiscardSettings;
+
+#[async_trait]
+impl NetworkProvider for DiscardProvider {
+    type NetworkSettings = DiscardSettings;
+
+    type Socket = ();
+
+    type ReadHalf = ();
+
+    type WriteHalf = ();
+
+    type ConnectInfo = ();
+
+    type AcceptInfo = ();
+
+    type AcceptStream = stream::Pending<()>;
+
+    async fn accept_loop(
+        _: Self::AcceptInfo,
+        _: Self::NetworkSettings,
+    ) -> Result<Self::AcceptStream, NetworkError> {
+        Ok(stream::pending())
+    }
+
+    async fn connect_task(
+        _: Self::ConnectInfo,
+        _: Self::NetworkSettings,
+    ) -> Result<Self::Socket, NetworkError> {
+        Ok(())
+    }
+
+    async fn recv_loop(_: Self::ReadHalf, _: Sender<NetworkPacket>, _: Self::NetworkSettings) {
+        future::pending::<()>().await
+    }
+
+    async fn send_loop(
+        _: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        _: Self::NetworkSettings,
+    ) {
+        while messages.recv().await.is_ok() {}
+    }
+
+    fn split(_: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        ((), ())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Payload(Vec<u8>);
+
+impl NetworkMessage for Payload {
+    const NAME: &'static str = "bench:Payload";
+}
+
+#[derive(Resource, Default)]
+struct Connected(Vec<ConnectionId>);
+
+fn track_connections(mut events: EventReader<NetworkEvent>, mut connected: ResMut<Connected>) {
+    for event in events.read() {
+        if let NetworkEvent::Connected(conn_id) = event {
+            connected.0.push(*conn_id);
+        }
+    }
+}
+
+/// Builds an app with `count` established connections
+fn app_with_connections(count: usize) -> App {
+    let mut app = App::new();
+    app.add_plugins(EventworkPlugin::<DiscardProvider, TaskPool>::default());
+    app.insert_resource(EventworkRuntime(
+        TaskPoolBuilder::new().num_threads(2).build(),
+    ));
+    app.insert_resource(DiscardSettings);
+    app.init_resource::<Connected>();
+    app.add_systems(Update, track_connections);
+
+    for _ in 0..count {
+        let net = app.world.resource::<Network<DiscardProvider>>();
+        let runtime = app.world.resource::<EventworkRuntime<TaskPool>>();
+        net.connect((), &runtime.0, &DiscardSettings);
+    }
+
+    while app.world.resource::<Connected>().0.len() < count {
+        app.update();
+    }
+
+    app
+}
+
+const CONNECTION_COUNTS: [usize; 3] = [1, 16, 256];
+const MESSAGE_SIZES: [usize; 2] = [64, 16 * 1024];
+
+fn bench_broadcast(c: &mut Criterion) {
+    for size in MESSAGE_SIZES {
+        let mut group = c.benchmark_group(format!("broadcast/{}B", size));
+        let payload = Payload(vec![0; size]);
+
+        for count in CONNECTION_COUNTS {
+            let app = app_with_connections(count);
+            let net = app.world.resource::<Network<DiscardProvider>>();
+            let connections = &app.world.resource::<Connected>().0;
+
+            group.throughput(Throughput::Elements(count as u64));
+
+            group.bench_with_input(BenchmarkId::new("broadcast", count), &count, |b, _| {
+                b.iter(|| net.broadcast(payload.clone()))
+            });
+
+            group.bench_with_input(BenchmarkId::new("broadcast_to", count), &count, |b, _| {
+                b.iter(|| {
+                    net.broadcast_to(connections.iter().copied(), payload.clone())
+                        .expect("Couldn't serialize message!")
+                })
+            });
+
+            group.bench_with_input(BenchmarkId::new("send_message", count), &count, |b, _| {
+                b.iter(|| {
+                    for conn_id in connections {
+                        net.send_message(*conn_id, payload.clone())
+                            .expect("Couldn't send message!");
+                    }
+                })
+            });
+        }
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_broadcast);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+//! The wire format used for messages.
+//!
+//! Every message, and every [`NetworkPacket`](crate::NetworkPacket) wrapping one, is encoded
+//! with bincode using these fixed options, independent of the platform:
+//!
+//! - Integers are fixed width and little endian, so a `u32` is always 4 bytes
+//! - Sequence and string lengths are a little endian `u64`, followed by the elements
+//! - Enum variants are tagged with a little endian `u32` index
+//! - There is no size limit, and trailing bytes are rejected when decoding
+//!
+//! This matches [`bincode::serialize`], so messages encoded by earlier versions stay compatible.
+//! Providers should frame packets with [`encode`] and [`decode`] to keep the same guarantees.
+//!
+//! ## Example
+//! These bytes are part of the wire format contract, and must never change.
+//! ```rust
+//! use bevy_eventwork::{codec, NetworkPacket};
+//!
+//! assert_eq!(codec::encode(&0x0102_0304u32).unwrap(), [4, 3, 2, 1]);
+//!
+//! let packet = NetworkPacket {
+//!     kind: String::from("ab"),
+//!     data: vec![7, 8],
+//! };
+//! let bytes = [
+//!     2, 0, 0, 0, 0, 0, 0, 0, b'a', b'b', // kind
+//!     2, 0, 0, 0, 0, 0, 0, 0, 7, 8, // data
+//! ];
+//! assert_eq!(codec::encode(&packet).unwrap(), bytes);
+//!
+//! let decoded: NetworkPacket = codec::decode(&bytes).unwrap();
+//! assert_eq!((decoded.kind.as_str(), decoded.data), ("ab", vec![7, 8]));
+//! ```
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The bincode options every eventwork message is encoded and decoded with.
+///
+/// Every option is spelled out, so a change to bincode's defaults can't change the wire format.
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .with_no_limit()
+        .reject_trailing_bytes()
+}
+
+/// Encodes a value in the eventwork wire format.
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> bincode::Result<Vec<u8>> {
+    options().serialize(value)
+}
+
+/// Decodes a value, failing unless it spans the whole of `bytes`.
+///
+/// Trailing bytes usually mean a framing bug or two apps disagreeing on a message layout,
+/// so they are reported as an error instead of being silently ignored.
+///
+/// ## Example
+/// ```rust
+/// use bevy_eventwork::codec;
+///
+/// let mut bytes = codec::encode(&42u32).unwrap();
+/// assert_eq!(codec::decode::<u32>(&bytes).unwrap(), 42);
+///
+/// bytes.extend_from_slice(&[0xde, 0xad]);
+/// assert!(codec::decode::<u32>(&bytes).is_err());
+/// ```
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    options().deserialize(bytes)
+}
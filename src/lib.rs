@@ -144,13 +144,28 @@ As you can see, they are both quite similar, and provide everything a basic netw
 Currently, Bevy's [TaskPool](bevy::tasks::TaskPool) is the default runtime used by Eventwork.
 */
 
+/// Logs at trace level from code that runs for every packet.
+///
+/// Compiled out entirely with the `strip-hot-path-logs` feature.
+#[cfg_attr(not(feature = "tcp"), allow(unused_macros))]
+macro_rules! hot_path_trace {
+    ($($arg:tt)*) => {
+        #[cfg(not(feature = "strip-hot-path-logs"))]
+        bevy::log::trace!($($arg)*)
+    };
+}
+
+pub mod codec;
 /// Contains error enum.
 pub mod error;
 mod network_message;
 
 /// Contains all functionality for starting a server or client, sending, and recieving messages from clients.
 pub mod managers;
-pub use managers::{network::AppNetworkMessage, Network};
+pub use managers::{
+    network::{AppNetworkMessage, DecodeErrorPolicy},
+    Network,
+};
 
 mod runtime;
 use managers::NetworkProvider;
@@ -161,6 +176,7 @@ pub use runtime::Runtime;
 use std::{
     fmt::{Debug, Display},
     marker::PhantomData,
+    sync::{atomic::AtomicBool, Arc},
 };
 
 pub use async_channel;
@@ -202,6 +218,18 @@ impl Display for ConnectionId {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Why a connection was closed by [`Network::disconnect_with_reason`]
+///
+/// Providers with a notion of close frames (websockets for instance) forward this to the remote,
+/// so it can tell a deliberate kick apart from a crash.
+pub struct CloseReason {
+    /// A provider specific status code, like a websocket close code
+    pub code: u16,
+    /// A human readable reason
+    pub reason: String,
+}
+
 #[derive(Serialize, Deserialize)]
 /// [`NetworkPacket`]s are untyped packets to be sent over the wire
 pub struct NetworkPacket {
@@ -219,6 +247,24 @@ impl Debug for NetworkPacket {
     }
 }
 
+#[derive(Default, Hash, PartialEq, Eq, Clone, Copy, Debug)]
+/// The priority a packet is queued with on its connection.
+///
+/// Each connection keeps one outbound queue per priority. Queued [`MessagePriority::High`]
+/// packets are handed to the provider ahead of any [`MessagePriority::Normal`] packet not yet
+/// handed off, so small control messages don't wait behind bulk data.
+///
+/// ## Note
+/// A normal packet already handed off can still go out first: the connection holds at most
+/// one waiting for the provider, besides the one the provider is currently sending.
+pub enum MessagePriority {
+    /// Sent ahead of every queued normal priority packet
+    High,
+    /// The priority used by [`Network::send_message`] and [`Network::broadcast`]
+    #[default]
+    Normal,
+}
+
 #[derive(Debug, Event)]
 /// A network event originating from another eventwork app
 pub enum NetworkEvent {
@@ -263,13 +309,31 @@ struct Connection {
     receive_task: Box<dyn JoinHandle>,
     map_receive_task: Box<dyn JoinHandle>,
     send_task: Box<dyn JoinHandle>,
+    priority_task: Box<dyn JoinHandle>,
     send_message: Sender<NetworkPacket>,
+    send_priority_message: Sender<NetworkPacket>,
+    close_connection: Sender<CloseReason>,
+    /// Set by whoever reports the end of a graceful close first, the send task or the close timeout
+    close_reported: Arc<AtomicBool>,
 }
 
 impl Connection {
+    fn sender(&self, priority: MessagePriority) -> &Sender<NetworkPacket> {
+        match priority {
+            MessagePriority::High => &self.send_priority_message,
+            MessagePriority::Normal => &self.send_message,
+        }
+    }
+
+    fn stop_receiving(&mut self) {
+        self.receive_task.abort();
+        self.map_receive_task.abort();
+    }
+
     fn stop(mut self) {
         self.receive_task.abort();
         self.send_task.abort();
+        self.priority_task.abort();
         self.map_receive_task.abort();
     }
 }
@@ -1,13 +1,17 @@
-use std::sync::{atomic::AtomicU32, Arc};
+use std::{
+    sync::{atomic::AtomicU32, Arc},
+    time::Duration,
+};
 
 use async_channel::{Receiver, Sender};
 use async_trait::async_trait;
-use bevy::prelude::Resource;
-use dashmap::DashMap;
+use bevy::{prelude::Resource, utils::Instant};
+use dashmap::{DashMap, DashSet};
 use futures_lite::Stream;
 
 use crate::{
-    error::NetworkError, runtime::JoinHandle, AsyncChannel, Connection, ConnectionId, NetworkPacket,
+    error::NetworkError, managers::network::DecodeFailures, runtime::JoinHandle, AsyncChannel,
+    CloseReason, Connection, ConnectionId, NetworkPacket,
 };
 
 /// Contains logic for using [`Network`]
@@ -26,10 +30,14 @@ pub mod network_request;
 #[derive(Resource)]
 pub struct Network<NP: NetworkProvider> {
     recv_message_map: Arc<DashMap<&'static str, Vec<(ConnectionId, Vec<u8>)>>>,
+    deregistered_messages: DashSet<&'static str>,
     established_connections: Arc<DashMap<ConnectionId, Connection>>,
     new_connections: AsyncChannel<NP::Socket>,
     disconnected_connections: AsyncChannel<ConnectionId>,
     error_channel: AsyncChannel<NetworkError>,
+    decode_failures: Arc<DecodeFailures>,
+    closing_connections: DashMap<ConnectionId, Instant>,
+    close_timeout: Duration,
     server_handle: Option<Box<dyn JoinHandle>>,
     connection_tasks: Arc<DashMap<u32, Box<dyn JoinHandle>>>,
     connection_task_counts: AtomicU32,
@@ -89,6 +97,25 @@ pub trait NetworkProvider: 'static + Send + Sync {
         settings: Self::NetworkSettings,
     );
 
+    /// Sends messages like [`Self::send_loop`], for connections that can be closed with a [`CloseReason`].
+    ///
+    /// This is what eventwork actually runs for every connection. When the connection is
+    /// disconnected with a reason, the reason is sent to `close`, and `messages` is closed
+    /// once everything queued before has been handed over. Providers with a notion of close
+    /// frames should override it to send one carrying the reason after `messages` is drained.
+    ///
+    /// ## Note
+    /// The default implementation sends every queued message and returns, ignoring the reason,
+    /// so the peer only sees the connection close. The TCP provider uses the default.
+    async fn send_loop_with_close(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        _close: Receiver<CloseReason>,
+        settings: Self::NetworkSettings,
+    ) {
+        Self::send_loop(write_half, messages, settings).await
+    }
+
     /// Split the socket into a read and write half, so that the two actions
     /// can be handled concurrently.
     fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf);
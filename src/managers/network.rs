@@ -1,22 +1,132 @@
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc,
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+    time::Duration,
 };
 
-use async_channel::unbounded;
-use bevy::prelude::*;
-use dashmap::DashMap;
-use futures_lite::StreamExt;
+use async_channel::{bounded, unbounded, Sender};
+use bevy::{prelude::*, utils::Instant};
+use dashmap::{DashMap, DashSet};
+use futures_lite::{future, StreamExt};
 
 use crate::{
+    codec,
     error::NetworkError,
     network_message::NetworkMessage,
     runtime::{run_async, EventworkRuntime},
-    AsyncChannel, Connection, ConnectionId, NetworkData, NetworkEvent, NetworkPacket, Runtime,
+    AsyncChannel, CloseReason, Connection, ConnectionId, MessagePriority, NetworkData,
+    NetworkEvent, NetworkPacket, Runtime,
 };
 
 use super::{Network, NetworkProvider};
 
+/// What a [`Network`] does when a packet received from a connection can't be decoded
+///
+/// This covers both packets of a message kind no one is listening for, and packets
+/// of a registered kind whose data fails to deserialize. Regardless of the policy,
+/// failures are counted per established connection, see [`Network::decode_failures`].
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeErrorPolicy {
+    /// Log the failure and drop the packet
+    #[default]
+    LogAndDrop,
+    /// Silently drop the packet, only counting the failure
+    Count,
+    /// Log the failure, drop the packet, and disconnect the client once it reaches `max_failures`
+    DisconnectClient {
+        /// The number of failures after which the client gets disconnected
+        ///
+        /// A `max_failures` of 0 is treated as 1, disconnecting on the first failure.
+        /// Failures counted before this policy was set count towards the limit, so a client
+        /// already past it is disconnected on its next failure.
+        max_failures: u32,
+    },
+}
+
+/// Shared bookkeeping for [`DecodeErrorPolicy`], also used from the receive tasks
+pub(crate) struct DecodeFailures {
+    policy: RwLock<DecodeErrorPolicy>,
+    counts: DashMap<ConnectionId, u32>,
+    /// Connections already queued for disconnecting, so each is only disconnected once
+    disconnecting: DashSet<ConnectionId>,
+    connections: Arc<DashMap<ConnectionId, Connection>>,
+    disconnect: Sender<ConnectionId>,
+}
+
+impl DecodeFailures {
+    fn new(
+        connections: Arc<DashMap<ConnectionId, Connection>>,
+        disconnect: Sender<ConnectionId>,
+    ) -> Self {
+        Self {
+            policy: RwLock::new(DecodeErrorPolicy::default()),
+            counts: DashMap::new(),
+            disconnecting: DashSet::new(),
+            connections,
+            disconnect,
+        }
+    }
+
+    fn policy(&self) -> DecodeErrorPolicy {
+        *self.policy.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn remove(&self, conn_id: &ConnectionId) {
+        self.counts.remove(conn_id);
+        self.disconnecting.remove(conn_id);
+    }
+
+    fn clear(&self) {
+        self.counts.clear();
+        self.disconnecting.clear();
+    }
+
+    pub(crate) fn record(&self, source: ConnectionId, kind: &str, reason: impl Display) {
+        // Injected packets can come from ids that were never connected, there is nothing to count
+        if !self.connections.contains_key(&source) {
+            error!(
+                "Failed to decode {} message from {}: {}",
+                kind, source, reason
+            );
+            return;
+        }
+
+        let count = {
+            let mut count = self.counts.entry(source).or_insert(0);
+            *count += 1;
+            *count
+        };
+
+        match self.policy() {
+            DecodeErrorPolicy::LogAndDrop => {
+                error!(
+                    "Failed to decode {} message from {}: {}",
+                    kind, source, reason
+                )
+            }
+            DecodeErrorPolicy::Count => (),
+            DecodeErrorPolicy::DisconnectClient { max_failures } => {
+                error!(
+                    "Failed to decode {} message from {}: {}",
+                    kind, source, reason
+                );
+                // Failures counted under an earlier policy count towards the limit too
+                if count >= max_failures.max(1) && self.disconnecting.insert(source) {
+                    warn!("Disconnecting {} after {} decode failures", source, count);
+                    if self.disconnect.try_send(source).is_err() {
+                        error!(
+                            "Could not send disconnected event, because channel is disconnected"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
 impl<NP: NetworkProvider> std::fmt::Debug for Network<NP> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -29,12 +139,23 @@ impl<NP: NetworkProvider> std::fmt::Debug for Network<NP> {
 
 impl<NP: NetworkProvider> Network<NP> {
     pub(crate) fn new(_provider: NP) -> Self {
+        let established_connections = Arc::new(DashMap::new());
+        let disconnected_connections = AsyncChannel::new();
+        let decode_failures = Arc::new(DecodeFailures::new(
+            established_connections.clone(),
+            disconnected_connections.sender.clone(),
+        ));
+
         Self {
             recv_message_map: Arc::new(DashMap::new()),
-            established_connections: Arc::new(DashMap::new()),
+            deregistered_messages: DashSet::new(),
+            established_connections,
             new_connections: AsyncChannel::new(),
-            disconnected_connections: AsyncChannel::new(),
+            disconnected_connections,
             error_channel: AsyncChannel::new(),
+            decode_failures,
+            closing_connections: DashMap::new(),
+            close_timeout: Duration::from_secs(5),
             server_handle: None,
             connection_tasks: Arc::new(DashMap::new()),
             connection_task_counts: AtomicU32::new(0),
@@ -45,7 +166,116 @@ impl<NP: NetworkProvider> Network<NP> {
     /// Returns true if there are any active connections
     #[inline(always)]
     pub fn has_connections(&self) -> bool {
-        self.established_connections.len() > 0
+        !self.established_connections.is_empty()
+    }
+
+    /// Set what happens when a packet from a connection can't be decoded
+    pub fn set_decode_error_policy(&self, policy: DecodeErrorPolicy) {
+        *self
+            .decode_failures
+            .policy
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = policy;
+    }
+
+    /// Set how long [`Network::disconnect_with_reason`] waits for queued messages to be sent
+    ///
+    /// ## Default
+    /// The default is 5 seconds
+    pub fn set_close_timeout(&mut self, timeout: Duration) {
+        self.close_timeout = timeout;
+    }
+
+    /// The number of packets from the given connection that could not be decoded
+    pub fn decode_failures(&self, conn_id: ConnectionId) -> u32 {
+        self.decode_failures
+            .counts
+            .get(&conn_id)
+            .map_or(0, |count| *count)
+    }
+
+    /// The names of all message kinds this network currently accepts
+    pub fn registered_messages(&self) -> Vec<&'static str> {
+        self.recv_message_map
+            .iter()
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
+    /// Stop accepting messages of the given kind, returning whether it was registered
+    ///
+    /// This is the counterpart to [`AppNetworkMessage::listen_for_message`], for apps that load
+    /// and unload plugins at runtime. Use [`NetworkMessage::NAME`] (or `REQUEST_NAME` for requests)
+    /// to name the kind.
+    ///
+    /// ## Note
+    /// Packets of that kind that are queued but not yet turned into events are dropped,
+    /// and ones received afterwards are handled by the [`DecodeErrorPolicy`] like any unknown kind.
+    /// The event type and its system stay in the app and keep running without any messages.
+    /// For a request, that includes the system turning them into
+    /// [`Request`](super::network_request::Request) events.
+    /// Use [`Network::reregister_message`] to accept the kind again.
+    pub fn deregister_message(&self, name: &str) -> bool {
+        match self.recv_message_map.remove(name) {
+            Some((name, _)) => {
+                self.deregistered_messages.insert(name);
+                debug!("Deregistered message: {}", name);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Accept messages of a kind removed with [`Network::deregister_message`] again,
+    /// returning whether it was deregistered
+    ///
+    /// Only the kind's entry is restored, reusing the event type and system that stayed in the app.
+    /// Listening for the message again on the [`App`] does the same.
+    pub fn reregister_message(&self, name: &str) -> bool {
+        match self.deregistered_messages.remove(name) {
+            Some(name) => {
+                // Never throw away packets already queued for a registered kind
+                self.recv_message_map.entry(name).or_default();
+                debug!("Reregistered message: {}", name);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start accepting a kind for the first time, see [`AppNetworkMessage::listen_for_message`]
+    pub(crate) fn register_message_kind(&self, name: &'static str) {
+        self.deregistered_messages.remove(name);
+        self.recv_message_map.insert(name, Vec::new());
+    }
+
+    /// Handle packets as if they were received from the given connection
+    ///
+    /// This is meant for replaying a recorded session, to reproduce a bug deterministically
+    /// or to turn it into a regression test. The packets are routed like any received packets,
+    /// and show up as events the next time the message systems run.
+    ///
+    /// ## Note
+    /// The connection id doesn't need to belong to an established connection, so a log can be
+    /// replayed under the ids it was recorded with, or mapped onto live ones by the caller.
+    /// Requests from an id that isn't connected can't be responded to and are dropped.
+    /// Packets from such an id that can't be decoded are only logged: they aren't counted
+    /// and the [`DecodeErrorPolicy`] never disconnects anyone for them.
+    /// Packets are handled as fast as possible; to respect the recorded timing,
+    /// inject each packet on the frame it is due.
+    pub fn inject_packets(
+        &self,
+        conn_id: ConnectionId,
+        packets: impl IntoIterator<Item = NetworkPacket>,
+    ) {
+        for packet in packets {
+            route_packet(
+                &self.recv_message_map,
+                &self.decode_failures,
+                conn_id,
+                packet,
+            );
+        }
     }
 
     /// Start listening for new clients
@@ -137,6 +367,19 @@ impl<NP: NetworkProvider> Network<NP> {
         &self,
         client_id: ConnectionId,
         message: T,
+    ) -> Result<(), NetworkError> {
+        self.send_message_with_priority(client_id, message, MessagePriority::Normal)
+    }
+
+    /// Send a message to a specific client, queued with the given [`MessagePriority`]
+    ///
+    /// ## Note
+    /// Ordering is only preserved between messages of the same priority.
+    pub fn send_message_with_priority<T: NetworkMessage>(
+        &self,
+        client_id: ConnectionId,
+        message: T,
+        priority: MessagePriority,
     ) -> Result<(), NetworkError> {
         let connection = match self.established_connections.get(&client_id) {
             Some(conn) => conn,
@@ -145,10 +388,10 @@ impl<NP: NetworkProvider> Network<NP> {
 
         let packet = NetworkPacket {
             kind: String::from(T::NAME),
-            data: bincode::serialize(&message).map_err(|_| NetworkError::Serialization)?,
+            data: codec::encode(&message).map_err(|_| NetworkError::Serialization)?,
         };
 
-        match connection.send_message.try_send(packet) {
+        match connection.sender(priority).try_send(packet) {
             Ok(_) => (),
             Err(err) => {
                 error!("There was an error sending a packet: {}", err);
@@ -161,26 +404,82 @@ impl<NP: NetworkProvider> Network<NP> {
 
     /// Broadcast a message to all connected clients
     pub fn broadcast<T: NetworkMessage + Clone>(&self, message: T) {
-        let serialized_message = bincode::serialize(&message).expect("Couldn't serialize message!");
+        self.broadcast_with_priority(message, MessagePriority::Normal)
+    }
+
+    /// Broadcast a message to all connected clients, queued with the given [`MessagePriority`]
+    pub fn broadcast_with_priority<T: NetworkMessage + Clone>(
+        &self,
+        message: T,
+        priority: MessagePriority,
+    ) {
+        let serialized_message = codec::encode(&message).expect("Couldn't serialize message!");
         for connection in self.established_connections.iter() {
             let packet = NetworkPacket {
                 kind: String::from(T::NAME),
                 data: serialized_message.clone(),
             };
 
-            match connection.send_message.try_send(packet) {
+            match connection.sender(priority).try_send(packet) {
+                Ok(_) => (),
+                Err(err) => {
+                    warn!("Could not send to client because: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Send a message to each of the given clients, serializing it only once
+    ///
+    /// This is useful when some other bookkeeping, like a list of subscribers,
+    /// decides who receives a message. Clients that are no longer connected are skipped.
+    pub fn broadcast_to<T: NetworkMessage>(
+        &self,
+        client_ids: impl IntoIterator<Item = ConnectionId>,
+        message: T,
+    ) -> Result<(), NetworkError> {
+        self.broadcast_to_with_priority(client_ids, message, MessagePriority::Normal)
+    }
+
+    /// Send a message to each of the given clients, queued with the given [`MessagePriority`]
+    pub fn broadcast_to_with_priority<T: NetworkMessage>(
+        &self,
+        client_ids: impl IntoIterator<Item = ConnectionId>,
+        message: T,
+        priority: MessagePriority,
+    ) -> Result<(), NetworkError> {
+        let serialized_message =
+            codec::encode(&message).map_err(|_| NetworkError::Serialization)?;
+        for client_id in client_ids {
+            let connection = match self.established_connections.get(&client_id) {
+                Some(conn) => conn,
+                None => {
+                    warn!("Skipping {}, because it is not connected", client_id);
+                    continue;
+                }
+            };
+
+            let packet = NetworkPacket {
+                kind: String::from(T::NAME),
+                data: serialized_message.clone(),
+            };
+
+            match connection.sender(priority).try_send(packet) {
                 Ok(_) => (),
                 Err(err) => {
                     warn!("Could not send to client because: {}", err);
                 }
             }
         }
+
+        Ok(())
     }
 
     /// Disconnect all clients and stop listening for new ones
     ///
     /// ## Notes
-    /// This operation is idempotent and will do nothing if you are not actively listening
+    /// This operation is idempotent and will do nothing if you are not actively listening.
+    /// All message kinds are deregistered, see [`Network::reregister_message`] to accept them again.
     pub fn stop(&mut self) {
         if let Some(mut conn) = self.server_handle.take() {
             conn.abort();
@@ -191,7 +490,13 @@ impl<NP: NetworkProvider> Network<NP> {
                 }
             }
             self.established_connections.clear();
+            self.closing_connections.clear();
+            // Remember the kinds, their systems stay in the app
+            for kind in self.recv_message_map.iter() {
+                self.deregistered_messages.insert(*kind.key());
+            }
             self.recv_message_map.clear();
+            self.decode_failures.clear();
 
             while self.new_connections.receiver.try_recv().is_ok() {}
         }
@@ -206,9 +511,68 @@ impl<NP: NetworkProvider> Network<NP> {
         };
 
         connection.1.stop();
+        self.closing_connections.remove(&conn_id);
+        self.decode_failures.remove(&conn_id);
 
         Ok(())
     }
+
+    /// Gracefully disconnect a specific client, telling it why
+    ///
+    /// Receiving stops immediately, while messages already queued for the client are still sent.
+    /// Whether the [`CloseReason`] reaches the client is up to the provider, see
+    /// [`NetworkProvider::send_loop_with_close`]. Once sending is done the connection is removed
+    /// and a [`NetworkEvent::Disconnected`] is sent.
+    ///
+    /// ## Note
+    /// A client that stops reading would keep the connection open forever, so sending is given up
+    /// after the close timeout, see [`Network::set_close_timeout`]. The connection is then
+    /// dropped with whatever is left in its queues, and the event is sent anyway.
+    /// Use [`Network::disconnect`] to drop a connection right away.
+    pub fn disconnect_with_reason(
+        &self,
+        conn_id: ConnectionId,
+        reason: CloseReason,
+    ) -> Result<(), NetworkError> {
+        let mut connection = match self.established_connections.get_mut(&conn_id) {
+            Some(conn) => conn,
+            None => return Err(NetworkError::ConnectionNotFound(conn_id)),
+        };
+
+        connection.stop_receiving();
+
+        connection
+            .close_connection
+            .try_send(reason)
+            .map_err(|_| NetworkError::ChannelClosed(conn_id))?;
+        // Closing the channel marks the connection as closing for its send task
+        connection.close_connection.close();
+        // Whatever is already queued still gets sent, then the send loop runs dry
+        connection.send_priority_message.close();
+        connection.send_message.close();
+
+        self.closing_connections
+            .insert(conn_id, Instant::now() + self.close_timeout);
+
+        Ok(())
+    }
+}
+
+/// Queues a received packet for the system turning its kind into events
+fn route_packet(
+    recv_message_map: &DashMap<&'static str, Vec<(ConnectionId, Vec<u8>)>>,
+    decode_failures: &DecodeFailures,
+    conn_id: ConnectionId,
+    packet: NetworkPacket,
+) {
+    match recv_message_map.get_mut(&packet.kind[..]) {
+        Some(mut packets) => packets.push((conn_id, packet.data)),
+        None => decode_failures.record(
+            conn_id,
+            &packet.kind,
+            "no listener registered for this message kind",
+        ),
+    }
 }
 
 pub(crate) fn handle_new_incoming_connections<NP: NetworkProvider, RT: Runtime>(
@@ -224,12 +588,22 @@ pub(crate) fn handle_new_incoming_connections<NP: NetworkProvider, RT: Runtime>(
 
         let (read_half, write_half) = NP::split(new_conn);
         let recv_message_map = server.recv_message_map.clone();
+        let decode_failures = server.decode_failures.clone();
         let read_network_settings = network_settings.clone();
         let write_network_settings = network_settings.clone();
         let disconnected_connections = server.disconnected_connections.sender.clone();
 
         let (outgoing_tx, outgoing_rx) = unbounded();
+        let (outgoing_priority_tx, outgoing_priority_rx) = unbounded();
+        // Only hand the provider one packet at a time, so high priority packets
+        // can overtake anything still waiting in the normal queue.
+        let (send_loop_tx, send_loop_rx) = bounded(1);
         let (incoming_tx, incoming_rx) = unbounded();
+        let (close_tx, close_rx) = bounded(1);
+        let closing = close_tx.clone();
+        let closed_connections = disconnected_connections.clone();
+        let close_reported = Arc::new(AtomicBool::new(false));
+        let send_close_reported = close_reported.clone();
 
         server.established_connections.insert(
                 conn_id,
@@ -247,19 +621,54 @@ pub(crate) fn handle_new_incoming_connections<NP: NetworkProvider, RT: Runtime>(
                     }, &runtime.0)),
                     map_receive_task: Box::new(run_async(async move{
                         while let Ok(packet) = incoming_rx.recv().await{
-                            match recv_message_map.get_mut(&packet.kind[..]) {
-                                Some(mut packets) => packets.push((conn_id, packet.data)),
-                                None => {
-                                    error!("Could not find existing entries for message kinds: {:?}", packet);
-                                }
-                            }
+                            route_packet(&recv_message_map, &decode_failures, conn_id, packet);
                         }
                     }, &runtime.0)),
                     send_task: Box::new(run_async(async move {
                         trace!("Starting send task for {}", id);
-                        NP::send_loop(write_half, outgoing_rx, write_network_settings).await;
+                        NP::send_loop_with_close(write_half, send_loop_rx, close_rx, write_network_settings).await;
+
+                        if closing.is_closed()
+                            && !send_close_reported.swap(true, Ordering::SeqCst)
+                            && closed_connections.send(conn_id).await.is_err()
+                        {
+                            error!("Could not send disconnected event, because channel is disconnected");
+                        }
+                    }, &runtime.0)),
+                    priority_task: Box::new(run_async(async move {
+                        loop {
+                            let packet = match outgoing_priority_rx.try_recv() {
+                                Ok(packet) => packet,
+                                Err(high) => match outgoing_rx.try_recv() {
+                                    Ok(packet) => packet,
+                                    // Both queues are closed and drained
+                                    Err(normal) if high.is_closed() && normal.is_closed() => break,
+                                    Err(normal) => {
+                                        let received = if high.is_closed() {
+                                            outgoing_rx.recv().await
+                                        } else if normal.is_closed() {
+                                            outgoing_priority_rx.recv().await
+                                        } else {
+                                            future::or(outgoing_priority_rx.recv(), outgoing_rx.recv()).await
+                                        };
+                                        match received {
+                                            Ok(packet) => packet,
+                                            // One of the queues was closed, check both again
+                                            Err(_) => continue,
+                                        }
+                                    }
+                                },
+                            };
+
+                            if send_loop_tx.send(packet).await.is_err() {
+                                break;
+                            }
+                        }
                     }, &runtime.0)),
                     send_message: outgoing_tx,
+                    send_priority_message: outgoing_priority_tx,
+                    close_connection: close_tx,
+                    close_reported,
                     //addr: new_conn.addr,
                 },
             );
@@ -271,8 +680,39 @@ pub(crate) fn handle_new_incoming_connections<NP: NetworkProvider, RT: Runtime>(
         server
             .established_connections
             .remove(&disconnected_connection);
+        server.closing_connections.remove(&disconnected_connection);
+        server.decode_failures.remove(&disconnected_connection);
         network_events.send(NetworkEvent::Disconnected(disconnected_connection));
     }
+
+    // Give up on graceful closes that didn't finish sending in time
+    let now = Instant::now();
+    let timed_out: Vec<ConnectionId> = server
+        .closing_connections
+        .iter()
+        .filter(|closing| *closing.value() <= now)
+        .map(|closing| *closing.key())
+        .collect();
+    for conn_id in timed_out {
+        server.closing_connections.remove(&conn_id);
+        let Some((_, connection)) = server.established_connections.remove(&conn_id) else {
+            continue;
+        };
+        // The send task may have finished just now, then it already reported the disconnect
+        let reported = connection.close_reported.swap(true, Ordering::SeqCst);
+        connection.stop();
+        if !reported {
+            warn!("Dropping {}, it didn't finish closing in time", conn_id);
+            server.decode_failures.remove(&conn_id);
+            network_events.send(NetworkEvent::Disconnected(conn_id));
+        }
+    }
+
+    // Errors from listening and connecting happen in background tasks, surface them here
+    while let Ok(error) = server.error_channel.receiver.try_recv() {
+        debug!("Network error: {}", error);
+        network_events.send(NetworkEvent::Error(error));
+    }
 }
 
 /// A utility trait on [`App`] to easily register [`NetworkMessage`]s
@@ -298,7 +738,11 @@ impl AppNetworkMessage for App {
             "Duplicate registration of ServerMessage: {}",
             T::NAME
         );
-        server.recv_message_map.insert(T::NAME, Vec::new());
+        // The event type and system are still in the app from the first registration
+        if server.reregister_message(T::NAME) {
+            return self;
+        }
+        server.register_message_kind(T::NAME);
         self.add_event::<NetworkData<T>>();
         self.add_systems(PreUpdate, register_message::<T, NP>)
     }
@@ -315,9 +759,14 @@ pub(crate) fn register_message<T, NP: NetworkProvider>(
         None => return,
     };
 
+    let decode_failures = &net_res.decode_failures;
     events.send_batch(messages.drain(..).filter_map(|(source, msg)| {
-        bincode::deserialize::<T>(&msg)
-            .ok()
-            .map(|inner| NetworkData { source, inner })
+        match codec::decode::<T>(&msg) {
+            Ok(inner) => Some(NetworkData { source, inner }),
+            Err(err) => {
+                decode_failures.record(source, T::NAME, err);
+                None
+            }
+        }
     }));
 }
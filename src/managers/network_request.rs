@@ -211,7 +211,7 @@ use bevy::{
 use dashmap::DashMap;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::{error::NetworkError, ConnectionId, NetworkData, NetworkMessage, NetworkPacket};
+use crate::{codec, error::NetworkError, ConnectionId, NetworkData, NetworkMessage, NetworkPacket};
 
 use super::{network::register_message, Network, NetworkProvider};
 
@@ -342,7 +342,7 @@ impl<T: RequestMessage> Request<T> {
     pub fn respond(self, response: T::ResponseMessage) -> Result<(), NetworkError> {
         let packet = NetworkPacket {
             kind: String::from(T::ResponseMessage::NAME),
-            data: bincode::serialize(&ResponseInternal {
+            data: codec::encode(&ResponseInternal {
                 response_id: self.request_id,
                 response,
             })
@@ -377,9 +377,11 @@ impl AppNetworkRequestMessage for App {
             "Duplicate registration of RequestMessage: {}",
             RequestInternal::<T>::NAME
         );
-        server
-            .recv_message_map
-            .insert(RequestInternal::<T>::NAME, Vec::new());
+        // The event types and systems are still in the app from the first registration
+        if server.reregister_message(RequestInternal::<T>::NAME) {
+            return self;
+        }
+        server.register_message_kind(RequestInternal::<T>::NAME);
         self.add_event::<NetworkData<RequestInternal<T>>>();
         self.add_event::<Request<T>>();
         self.add_systems(
@@ -427,7 +429,6 @@ pub trait AppNetworkResponseMessage {
 
 impl AppNetworkResponseMessage for App {
     fn listen_for_response_message<T: RequestMessage, NP: NetworkProvider>(&mut self) -> &mut Self {
-        self.insert_resource(ResponseMap::<T>::default());
         let client = self.world.get_resource::<Network<NP>>().expect("Could not find `Network`. Be sure to include the `EventworkPlugin` before listening for server messages.");
 
         debug!(
@@ -442,9 +443,13 @@ impl AppNetworkResponseMessage for App {
             "Duplicate registration of ResponseMessage: {}",
             ResponseInternal::<T::ResponseMessage>::NAME
         );
-        client
-            .recv_message_map
-            .insert(ResponseInternal::<T::ResponseMessage>::NAME, Vec::new());
+        // The response map, event type and systems are still in the app from the first
+        // registration, keep them so pending responses aren't dropped
+        if client.reregister_message(ResponseInternal::<T::ResponseMessage>::NAME) {
+            return self;
+        }
+        client.register_message_kind(ResponseInternal::<T::ResponseMessage>::NAME);
+        self.insert_resource(ResponseMap::<T>::default());
         self.add_event::<NetworkData<ResponseInternal<T::ResponseMessage>>>();
         self.add_systems(
             PreUpdate,
@@ -1,15 +1,16 @@
-use std::{net::SocketAddr, pin::Pin};
+use std::{io, net::SocketAddr, pin::Pin, time::Duration};
 
 use crate::{
     async_channel::{Receiver, Sender},
-    async_trait,
+    async_trait, codec,
     error::NetworkError,
     managers::NetworkProvider,
     NetworkPacket,
 };
+use async_io::Timer;
 use async_net::{TcpListener, TcpStream};
 use bevy::{
-    log::{debug, error, info, trace},
+    log::{debug, error, info},
     prelude::Resource,
 };
 use futures_lite::{AsyncReadExt, AsyncWriteExt, FutureExt, Stream};
@@ -49,12 +50,25 @@ impl NetworkProvider for TcpProvider {
 
     async fn connect_task(
         connect_info: Self::ConnectInfo,
-        _: Self::NetworkSettings,
+        settings: Self::NetworkSettings,
     ) -> Result<Self::Socket, NetworkError> {
         info!("Beginning connection");
-        let stream = TcpStream::connect(connect_info)
-            .await
-            .map_err(NetworkError::Connection)?;
+        let connect = TcpStream::connect(connect_info);
+        let stream = match settings.connect_timeout {
+            Some(timeout) => {
+                connect
+                    .or(async move {
+                        Timer::after(timeout).await;
+                        Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("connection timed out after {:?}", timeout),
+                        ))
+                    })
+                    .await
+            }
+            None => connect.await,
+        }
+        .map_err(NetworkError::Connection)?;
 
         info!("Connected!");
 
@@ -73,7 +87,7 @@ impl NetworkProvider for TcpProvider {
     ) {
         let mut buffer = vec![0; settings.max_packet_length];
         loop {
-            info!("Reading message length");
+            hot_path_trace!("Reading message length");
             let length = match read_half.read(&mut buffer[..8]).await {
                 Ok(0) => {
                     // EOF, meaning the TCP stream has closed.
@@ -102,7 +116,7 @@ impl NetworkProvider for TcpProvider {
                     break;
                 }
             };
-            info!("Message length: {}", length);
+            hot_path_trace!("Message length: {}", length);
 
             if length > settings.max_packet_length {
                 error!(
@@ -112,7 +126,7 @@ impl NetworkProvider for TcpProvider {
                 break;
             }
 
-            info!("Reading message into buffer");
+            hot_path_trace!("Reading message into buffer");
             match read_half.read_exact(&mut buffer[..length]).await {
                 Ok(()) => (),
                 Err(err) => {
@@ -123,9 +137,9 @@ impl NetworkProvider for TcpProvider {
                     break;
                 }
             }
-            info!("Message read");
+            hot_path_trace!("Message read");
 
-            let packet: NetworkPacket = match bincode::deserialize(&buffer[..length]) {
+            let packet: NetworkPacket = match codec::decode(&buffer[..length]) {
                 Ok(packet) => packet,
                 Err(err) => {
                     error!("Failed to decode network packet from: {}", err);
@@ -137,7 +151,7 @@ impl NetworkProvider for TcpProvider {
                 error!("Failed to send decoded message to eventwork");
                 break;
             }
-            info!("Message deserialized and sent to eventwork");
+            hot_path_trace!("Message deserialized and sent to eventwork");
         }
     }
 
@@ -147,7 +161,7 @@ impl NetworkProvider for TcpProvider {
         _settings: Self::NetworkSettings,
     ) {
         while let Ok(message) = messages.recv().await {
-            let encoded = match bincode::serialize(&message) {
+            let encoded = match codec::encode(&message) {
                 Ok(encoded) => encoded,
                 Err(err) => {
                     error!("Could not encode packet {:?}: {}", message, err);
@@ -156,8 +170,9 @@ impl NetworkProvider for TcpProvider {
             };
 
             let len = encoded.len() as u64;
-            debug!("Sending a new message of size: {}", len);
+            hot_path_trace!("Sending a new message of size: {}", len);
 
+            // The frame length is always little endian, like the rest of the wire format
             match write_half.write(&len.to_le_bytes()).await {
                 Ok(_) => (),
                 Err(err) => {
@@ -166,7 +181,7 @@ impl NetworkProvider for TcpProvider {
                 }
             }
 
-            trace!("Sending the content of the message!");
+            hot_path_trace!("Sending the content of the message!");
 
             match write_half.write_all(&encoded).await {
                 Ok(_) => (),
@@ -176,7 +191,7 @@ impl NetworkProvider for TcpProvider {
                 }
             }
 
-            trace!("Succesfully written all!");
+            hot_path_trace!("Succesfully written all!");
         }
     }
 
@@ -194,12 +209,19 @@ pub struct NetworkSettings {
     /// ## Default
     /// The default is set to 10MiB
     pub max_packet_length: usize,
+    /// How long a client waits for a connection to be established before giving up.
+    /// On timeout, the error is sent as a [`NetworkEvent::Error`](crate::NetworkEvent::Error).
+    ///
+    /// ## Default
+    /// The default is `None`, leaving it up to the operating system
+    pub connect_timeout: Option<Duration>,
 }
 
 impl Default for NetworkSettings {
     fn default() -> Self {
         Self {
             max_packet_length: 10 * 1024 * 1024,
+            connect_timeout: None,
         }
     }
 }
@@ -207,7 +229,7 @@ impl Default for NetworkSettings {
 /// A special stream for recieving tcp connections
 pub struct OwnedIncoming {
     inner: TcpListener,
-    stream: Option<Pin<Box<dyn Future<Output = Option<TcpStream>>>>>,
+    stream: Option<Pin<Box<dyn Future<Output = Option<TcpStream>> + Send>>>,
 }
 
 impl OwnedIncoming {
@@ -228,17 +250,10 @@ impl Stream for OwnedIncoming {
     ) -> std::task::Poll<Option<Self::Item>> {
         let incoming = self.get_mut();
         if incoming.stream.is_none() {
-            let listener: *const TcpListener = &incoming.inner;
+            // The future owns a handle to the listener, so it can't outlive it
+            let listener = incoming.inner.clone();
             incoming.stream = Some(Box::pin(async move {
-                unsafe {
-                    listener
-                        .as_ref()
-                        .expect("Segfault when trying to read listener in OwnedStream")
-                }
-                .accept()
-                .await
-                .map(|(s, _)| s)
-                .ok()
+                listener.accept().await.map(|(s, _)| s).ok()
             }));
         }
         if let Some(stream) = &mut incoming.stream {
@@ -250,5 +265,3 @@ impl Stream for OwnedIncoming {
         std::task::Poll::Pending
     }
 }
-
-unsafe impl Send for OwnedIncoming {}
@@ -0,0 +1,148 @@
+#![allow(dead_code)]
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{TaskPool, TaskPoolBuilder},
+};
+use bevy_eventwork::{
+    codec,
+    error::NetworkError,
+    tcp::{NetworkSettings, TcpProvider},
+    EventworkPlugin, EventworkRuntime, Network, NetworkEvent, NetworkMessage, NetworkPacket,
+};
+use serde::{Deserialize, Serialize};
+
+/// How long to wait for an event before failing a test
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A small message for tests that only care about registration and decoding
+#[derive(Serialize, Deserialize)]
+pub struct Ping(pub u64);
+
+impl NetworkMessage for Ping {
+    const NAME: &'static str = "test:Ping";
+}
+
+/// Builds an app with the TCP provider and default settings
+pub fn app() -> App {
+    let mut app = App::new();
+    app.add_plugins(EventworkPlugin::<TcpProvider, TaskPool>::default());
+    app.insert_resource(EventworkRuntime(
+        TaskPoolBuilder::new().num_threads(2).build(),
+    ));
+    app.insert_resource(NetworkSettings::default());
+    app
+}
+
+/// A loopback address nothing is listening on
+pub fn unused_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Couldn't find a free port")
+}
+
+/// Starts listening on `addr`
+pub fn listen_on(app: &mut App, addr: SocketAddr) -> Result<(), NetworkError> {
+    app.world
+        .resource_scope(|world, mut net: Mut<Network<TcpProvider>>| {
+            let runtime = world.resource::<EventworkRuntime<TaskPool>>();
+            net.listen(addr, &runtime.0, world.resource::<NetworkSettings>())
+        })
+}
+
+/// Connects to `addr` with the app's [`NetworkSettings`]
+pub fn connect(app: &mut App, addr: SocketAddr) {
+    let runtime = app.world.resource::<EventworkRuntime<TaskPool>>();
+    let settings = app.world.resource::<NetworkSettings>();
+    app.world
+        .resource::<Network<TcpProvider>>()
+        .connect(addr, &runtime.0, settings);
+}
+
+/// Starts listening on a free loopback address, returning it
+pub fn listen(app: &mut App) -> SocketAddr {
+    let addr = unused_addr();
+    listen_on(app, addr).expect("Couldn't start listening");
+    addr
+}
+
+/// Updates the app until it sends a [`NetworkEvent`] matching `predicate`, skipping any others
+pub fn wait_for_event(
+    app: &mut App,
+    mut predicate: impl FnMut(&NetworkEvent) -> bool,
+) -> NetworkEvent {
+    let start = Instant::now();
+    loop {
+        app.update();
+        let mut events = app.world.resource_mut::<Events<NetworkEvent>>();
+        if let Some(event) = events.drain().find(|event| predicate(event)) {
+            return event;
+        }
+        assert!(start.elapsed() < TIMEOUT, "Timed out waiting for an event");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// Updates the app until `condition` holds
+pub fn update_until(app: &mut App, mut condition: impl FnMut(&App) -> bool) {
+    let start = Instant::now();
+    while !condition(app) {
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "Timed out waiting for a condition"
+        );
+        thread::sleep(Duration::from_millis(1));
+        app.update();
+    }
+}
+
+/// Connects a plain TCP client and waits for the app to accept it
+pub fn connect_client(
+    app: &mut App,
+    addr: SocketAddr,
+) -> (TcpStream, bevy_eventwork::ConnectionId) {
+    // The app binds its listener asynchronously, so the first attempts may be refused
+    let start = Instant::now();
+    let client = loop {
+        match TcpStream::connect(addr) {
+            Ok(client) => break client,
+            Err(err) => assert!(start.elapsed() < TIMEOUT, "Couldn't connect: {}", err),
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    client
+        .set_read_timeout(Some(TIMEOUT))
+        .expect("Couldn't set read timeout");
+    match wait_for_event(app, |event| matches!(event, NetworkEvent::Connected(_))) {
+        NetworkEvent::Connected(conn_id) => (client, conn_id),
+        _ => unreachable!(),
+    }
+}
+
+/// Writes a packet framed the way the TCP provider reads it
+pub fn write_packet(stream: &mut TcpStream, packet: &NetworkPacket) {
+    let encoded = codec::encode(packet).expect("Couldn't encode packet");
+    let mut frame = (encoded.len() as u64).to_le_bytes().to_vec();
+    frame.extend(encoded);
+    stream.write_all(&frame).expect("Couldn't write packet");
+}
+
+/// Reads a packet framed by the TCP provider, or `None` once the stream is closed
+pub fn read_packet(stream: &mut TcpStream) -> Option<NetworkPacket> {
+    let mut length = [0; 8];
+    match stream.read_exact(&mut length) {
+        Ok(()) => (),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return None,
+        Err(err) => panic!("Couldn't read packet length: {}", err),
+    }
+    let mut data = vec![0; u64::from_le_bytes(length) as usize];
+    stream.read_exact(&mut data).expect("Couldn't read packet");
+    Some(codec::decode(&data).expect("Couldn't decode packet"))
+}
@@ -0,0 +1,64 @@
+// Relies on Linux dropping connection attempts to a listener with a full backlog,
+// other platforms refuse them instead
+#![cfg(target_os = "linux")]
+
+use std::{
+    io,
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+use bevy_eventwork::{error::NetworkError, tcp::NetworkSettings, NetworkEvent};
+use socket2::{Domain, Socket, Type};
+
+mod common;
+
+#[test]
+fn connecting_to_an_unresponsive_server_times_out() {
+    // Nothing accepts on this socket, so once its backlog is full further connects hang
+    let listener = Socket::new(Domain::IPV4, Type::STREAM, None).expect("Couldn't create socket");
+    listener
+        .bind(&SocketAddr::from(([127, 0, 0, 1], 0)).into())
+        .expect("Couldn't bind a port");
+    listener.listen(0).expect("Couldn't listen");
+    let addr = listener
+        .local_addr()
+        .ok()
+        .and_then(|addr| addr.as_socket())
+        .expect("Couldn't get the bound address");
+
+    let mut backlog = Vec::new();
+    loop {
+        match TcpStream::connect_timeout(&addr, Duration::from_millis(100)) {
+            Ok(stream) => backlog.push(stream),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => break,
+            Err(err) => panic!("Couldn't fill the backlog: {}", err),
+        }
+        assert!(backlog.len() < 16, "The backlog never filled up");
+    }
+
+    let timeout = Duration::from_millis(300);
+    let mut app = common::app();
+    app.insert_resource(NetworkSettings {
+        connect_timeout: Some(timeout),
+        ..Default::default()
+    });
+
+    let start = Instant::now();
+    common::connect(&mut app, addr);
+    let event = common::wait_for_event(&mut app, |event| matches!(event, NetworkEvent::Error(_)));
+    let elapsed = start.elapsed();
+
+    match event {
+        NetworkEvent::Error(NetworkError::Connection(err)) => {
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut)
+        }
+        other => panic!("Expected a connection error, got {:?}", other),
+    }
+    assert!(
+        elapsed >= timeout && elapsed < timeout + Duration::from_secs(1),
+        "Timed out after {:?} instead of {:?}",
+        elapsed,
+        timeout
+    );
+}
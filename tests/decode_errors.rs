@@ -0,0 +1,124 @@
+use bevy::prelude::*;
+use bevy_eventwork::{
+    tcp::TcpProvider, AppNetworkMessage, ConnectionId, DecodeErrorPolicy, Network, NetworkEvent,
+    NetworkMessage, NetworkPacket,
+};
+
+mod common;
+
+use common::Ping;
+
+fn unknown_packet() -> NetworkPacket {
+    NetworkPacket {
+        kind: String::from("test:Unknown"),
+        data: Vec::new(),
+    }
+}
+
+fn malformed_ping() -> NetworkPacket {
+    NetworkPacket {
+        kind: String::from(Ping::NAME),
+        data: vec![1, 2, 3],
+    }
+}
+
+#[test]
+fn injected_failures_from_unknown_ids_are_not_counted() {
+    let mut app = common::app();
+    app.listen_for_message::<Ping, TcpProvider>();
+
+    let ghost = ConnectionId { id: 99 };
+    let net = app.world.resource::<Network<TcpProvider>>();
+    net.set_decode_error_policy(DecodeErrorPolicy::DisconnectClient { max_failures: 2 });
+    net.inject_packets(
+        ghost,
+        [unknown_packet(), unknown_packet(), unknown_packet()],
+    );
+    net.inject_packets(ghost, [malformed_ping(), malformed_ping()]);
+
+    assert_eq!(net.decode_failures(ghost), 0);
+
+    for _ in 0..10 {
+        app.update();
+        let mut events = app.world.resource_mut::<Events<NetworkEvent>>();
+        assert!(!events
+            .drain()
+            .any(|event| matches!(event, NetworkEvent::Disconnected(_))));
+    }
+}
+
+#[test]
+fn strict_policy_disconnects_after_max_failures() {
+    let mut app = common::app();
+    app.listen_for_message::<Ping, TcpProvider>();
+    app.world
+        .resource::<Network<TcpProvider>>()
+        .set_decode_error_policy(DecodeErrorPolicy::DisconnectClient { max_failures: 2 });
+    let addr = common::listen(&mut app);
+    let (mut client, conn_id) = common::connect_client(&mut app, addr);
+
+    common::write_packet(&mut client, &malformed_ping());
+    common::update_until(&mut app, |app| {
+        app.world
+            .resource::<Network<TcpProvider>>()
+            .decode_failures(conn_id)
+            == 1
+    });
+    for _ in 0..10 {
+        app.update();
+        let mut events = app.world.resource_mut::<Events<NetworkEvent>>();
+        assert!(!events
+            .drain()
+            .any(|event| matches!(event, NetworkEvent::Disconnected(_))));
+    }
+
+    common::write_packet(&mut client, &malformed_ping());
+    common::wait_for_event(
+        &mut app,
+        |event| matches!(event, NetworkEvent::Disconnected(id) if *id == conn_id),
+    );
+    assert_eq!(
+        app.world
+            .resource::<Network<TcpProvider>>()
+            .decode_failures(conn_id),
+        0
+    );
+    assert!(common::read_packet(&mut client).is_none());
+}
+
+#[test]
+fn switching_to_strict_policy_disconnects_clients_past_the_limit() {
+    let mut app = common::app();
+    app.listen_for_message::<Ping, TcpProvider>();
+    let addr = common::listen(&mut app);
+    let (mut client, conn_id) = common::connect_client(&mut app, addr);
+
+    for _ in 0..3 {
+        common::write_packet(&mut client, &malformed_ping());
+    }
+    common::update_until(&mut app, |app| {
+        app.world
+            .resource::<Network<TcpProvider>>()
+            .decode_failures(conn_id)
+            == 3
+    });
+
+    app.world
+        .resource::<Network<TcpProvider>>()
+        .set_decode_error_policy(DecodeErrorPolicy::DisconnectClient { max_failures: 2 });
+    for _ in 0..5 {
+        common::write_packet(&mut client, &malformed_ping());
+    }
+    common::wait_for_event(
+        &mut app,
+        |event| matches!(event, NetworkEvent::Disconnected(id) if *id == conn_id),
+    );
+
+    for _ in 0..10 {
+        app.update();
+        let mut events = app.world.resource_mut::<Events<NetworkEvent>>();
+        assert!(!events
+            .drain()
+            .any(|event| matches!(event, NetworkEvent::Disconnected(_))));
+    }
+}
@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+use bevy_eventwork::{codec, tcp::TcpProvider, CloseReason, Network, NetworkEvent, NetworkMessage};
+use serde::{Deserialize, Serialize};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+mod common;
+
+/// Large enough that the queued chunks don't all fit in the socket buffers
+#[derive(Serialize, Deserialize)]
+struct Chunk(u32, Vec<u8>);
+
+impl NetworkMessage for Chunk {
+    const NAME: &'static str = "test:Chunk";
+}
+
+fn close_reason() -> CloseReason {
+    CloseReason {
+        code: 1000,
+        reason: String::from("done"),
+    }
+}
+
+#[test]
+fn disconnect_with_reason_sends_queued_messages() {
+    let mut app = common::app();
+    let addr = common::listen(&mut app);
+    let (mut client, conn_id) = common::connect_client(&mut app, addr);
+
+    let net = app.world.resource::<Network<TcpProvider>>();
+    for i in 0..100 {
+        net.send_message(conn_id, Chunk(i, vec![0; 64 * 1024]))
+            .expect("Couldn't send message");
+    }
+    net.disconnect_with_reason(conn_id, close_reason())
+        .expect("Couldn't disconnect");
+
+    let reader = thread::spawn(move || {
+        let mut received = Vec::new();
+        while let Some(packet) = common::read_packet(&mut client) {
+            assert_eq!(packet.kind, Chunk::NAME);
+            let Chunk(i, _) = codec::decode(&packet.data).expect("Couldn't decode message");
+            received.push(i);
+        }
+        received
+    });
+
+    common::wait_for_event(
+        &mut app,
+        |event| matches!(event, NetworkEvent::Disconnected(id) if *id == conn_id),
+    );
+
+    let received = reader.join().expect("Reader panicked");
+    assert_eq!(received, (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn disconnect_with_reason_gives_up_on_stalled_clients() {
+    let mut app = common::app();
+    let timeout = Duration::from_millis(300);
+    app.world
+        .resource_mut::<Network<TcpProvider>>()
+        .set_close_timeout(timeout);
+    let addr = common::listen(&mut app);
+    // Never reads, so the queued chunks can't all be sent
+    let (_client, conn_id) = common::connect_client(&mut app, addr);
+
+    let net = app.world.resource::<Network<TcpProvider>>();
+    for i in 0..100 {
+        net.send_message(conn_id, Chunk(i, vec![0; 64 * 1024]))
+            .expect("Couldn't send message");
+    }
+    let start = Instant::now();
+    net.disconnect_with_reason(conn_id, close_reason())
+        .expect("Couldn't disconnect");
+
+    common::wait_for_event(
+        &mut app,
+        |event| matches!(event, NetworkEvent::Disconnected(id) if *id == conn_id),
+    );
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed >= timeout && elapsed < timeout + Duration::from_secs(1),
+        "Disconnected after {:?} instead of {:?}",
+        elapsed,
+        timeout
+    );
+    assert!(!app
+        .world
+        .resource::<Network<TcpProvider>>()
+        .has_connections());
+
+    for _ in 0..10 {
+        app.update();
+        let mut events = app.world.resource_mut::<Events<NetworkEvent>>();
+        assert!(!events
+            .drain()
+            .any(|event| matches!(event, NetworkEvent::Disconnected(_))));
+    }
+}
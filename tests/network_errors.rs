@@ -0,0 +1,21 @@
+use std::net::TcpListener;
+
+use bevy_eventwork::{error::NetworkError, NetworkEvent};
+
+mod common;
+
+#[test]
+fn listening_on_a_taken_port_sends_an_error() {
+    let taken = TcpListener::bind("127.0.0.1:0").expect("Couldn't bind a port");
+    let addr = taken.local_addr().expect("Couldn't get the bound address");
+
+    let mut app = common::app();
+    common::listen_on(&mut app, addr).expect("Listen failed before starting its task");
+
+    let event = common::wait_for_event(&mut app, |event| matches!(event, NetworkEvent::Error(_)));
+    assert!(
+        matches!(event, NetworkEvent::Error(NetworkError::Listen(_))),
+        "Expected a listen error, got {:?}",
+        event
+    );
+}
@@ -0,0 +1,147 @@
+use std::time::Instant;
+
+use bevy::{ecs::system::RunSystemOnce, prelude::*};
+use bevy_eventwork::{
+    codec,
+    managers::network_request::{AppNetworkResponseMessage, RequestMessage, Requester},
+    tcp::TcpProvider,
+    AppNetworkMessage, ConnectionId, Network, NetworkData, NetworkMessage, NetworkPacket,
+};
+use serde::{Deserialize, Serialize};
+
+mod common;
+
+use common::Ping;
+
+/// Injects a ping and returns how many ping events the next update sends
+fn receive_ping(app: &mut App) -> usize {
+    let packet = NetworkPacket {
+        kind: String::from(Ping::NAME),
+        data: codec::encode(&Ping(7)).expect("Couldn't encode message"),
+    };
+    app.world
+        .resource::<Network<TcpProvider>>()
+        .inject_packets(ConnectionId { id: 0 }, [packet]);
+    app.update();
+    app.world
+        .resource_mut::<Events<NetworkData<Ping>>>()
+        .drain()
+        .count()
+}
+
+#[test]
+fn deregistered_messages_can_be_registered_again() {
+    let mut app = common::app();
+    app.listen_for_message::<Ping, TcpProvider>();
+    assert_eq!(receive_ping(&mut app), 1);
+
+    let net = app.world.resource::<Network<TcpProvider>>();
+    assert!(!net.reregister_message(Ping::NAME));
+    assert!(net.deregister_message(Ping::NAME));
+    assert!(!net.deregister_message(Ping::NAME));
+    assert!(net.registered_messages().is_empty());
+    assert_eq!(receive_ping(&mut app), 0);
+
+    let net = app.world.resource::<Network<TcpProvider>>();
+    assert!(net.reregister_message(Ping::NAME));
+    assert_eq!(net.registered_messages(), [Ping::NAME]);
+    assert_eq!(receive_ping(&mut app), 1);
+}
+
+#[test]
+fn listening_again_reuses_the_registration() {
+    let mut app = common::app();
+    app.listen_for_message::<Ping, TcpProvider>();
+    app.world
+        .resource::<Network<TcpProvider>>()
+        .deregister_message(Ping::NAME);
+
+    assert!(app
+        .world
+        .resource::<Network<TcpProvider>>()
+        .reregister_message(Ping::NAME));
+    assert_eq!(receive_ping(&mut app), 1);
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct StatusRequest;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct StatusResponse(bool);
+
+impl NetworkMessage for StatusResponse {
+    const NAME: &'static str = "test:StatusResponse";
+}
+
+impl RequestMessage for StatusRequest {
+    type ResponseMessage = StatusResponse;
+    const REQUEST_NAME: &'static str = "test:StatusRequest";
+}
+
+#[test]
+fn listening_for_responses_again_keeps_pending_requests() {
+    let mut app = common::app();
+    app.listen_for_response_message::<StatusRequest, TcpProvider>();
+    let addr = common::listen(&mut app);
+    let (mut client, conn_id) = common::connect_client(&mut app, addr);
+
+    let mut response = app
+        .world
+        .run_system_once(move |requester: Requester<StatusRequest, TcpProvider>| {
+            requester.send_request(conn_id, StatusRequest)
+        })
+        .expect("Couldn't send request");
+
+    app.world
+        .resource::<Network<TcpProvider>>()
+        .deregister_message(StatusResponse::NAME);
+    app.listen_for_response_message::<StatusRequest, TcpProvider>();
+    assert!(!app
+        .world
+        .resource::<Network<TcpProvider>>()
+        .reregister_message(StatusResponse::NAME));
+
+    // Requests are sent as their id followed by the request itself
+    let request = common::read_packet(&mut client).expect("Connection closed");
+    assert_eq!(request.kind, StatusRequest::REQUEST_NAME);
+    let (id, StatusRequest): (u64, StatusRequest) =
+        codec::decode(&request.data).expect("Couldn't decode request");
+    common::write_packet(
+        &mut client,
+        &NetworkPacket {
+            kind: String::from(StatusResponse::NAME),
+            data: codec::encode(&(id, StatusResponse(true))).expect("Couldn't encode response"),
+        },
+    );
+
+    let start = Instant::now();
+    loop {
+        app.update();
+        response = match response.try_recv() {
+            Ok(status) => break assert_eq!(status, StatusResponse(true)),
+            Err(response) => response,
+        };
+        assert!(
+            start.elapsed() < common::TIMEOUT,
+            "Timed out waiting for the response"
+        );
+    }
+}
+
+#[test]
+fn stopping_deregisters_every_message() {
+    let mut app = common::app();
+    app.listen_for_message::<Ping, TcpProvider>();
+    common::listen(&mut app);
+    app.world.resource_mut::<Network<TcpProvider>>().stop();
+    assert!(app
+        .world
+        .resource::<Network<TcpProvider>>()
+        .registered_messages()
+        .is_empty());
+    assert!(app
+        .world
+        .resource::<Network<TcpProvider>>()
+        .reregister_message(Ping::NAME));
+    assert_eq!(receive_ping(&mut app), 1);
+}